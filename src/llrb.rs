@@ -1,15 +1,107 @@
+use std::borrow::Borrow;
 use std::boxed::Box;
+use std::cell::Cell;
 use std::cmp::Ord;
 use std::cmp::Ordering;
+use std::collections::TryReserveError;
 use std::fmt;
+use std::iter::FromIterator;
+use std::ops::{Bound, RangeBounds};
+use std::rc::Rc;
+
+/// Hash of an absent subtree, used as the fixed contribution of a `None`
+/// child when folding node hashes.
+const EMPTY_HASH: [u8; 32] = [0u8; 32];
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Combines the given byte strings into a 256-bit SHA-256 digest.
+fn hash_bytes(parts: &[&[u8]]) -> [u8; 32] {
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a,
+        0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+    ];
+
+    let mut msg: Vec<u8> = Vec::new();
+    for p in parts {
+        msg.extend_from_slice(p);
+    }
+    let bit_len = (msg.len() as u64) * 8;
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&bit_len.to_be_bytes());
+
+    for block in msg.chunks(64) {
+        let mut w = [0u32; 64];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes([block[4 * i], block[4 * i + 1], block[4 * i + 2], block[4 * i + 3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let (mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh) =
+            (h[0], h[1], h[2], h[3], h[4], h[5], h[6], h[7]);
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ ((!e) & g);
+            let temp1 = hh.wrapping_add(s1).wrapping_add(ch).wrapping_add(SHA256_K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(maj);
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+
+        h[0] = h[0].wrapping_add(a);
+        h[1] = h[1].wrapping_add(b);
+        h[2] = h[2].wrapping_add(c);
+        h[3] = h[3].wrapping_add(d);
+        h[4] = h[4].wrapping_add(e);
+        h[5] = h[5].wrapping_add(f);
+        h[6] = h[6].wrapping_add(g);
+        h[7] = h[7].wrapping_add(hh);
+    }
+
+    let mut out = [0u8; 32];
+    for (i, word) in h.iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    out
+}
 
 #[derive(Debug, Clone)]
 struct Node<K, V> where K: Ord + Clone, V: Clone {
     key: Box<K>,
     val: Box<V>,
     color: bool,
-    left: Option<Box<Node<K, V>>>,
-    right: Option<Box<Node<K, V>>>,
+    size: usize,
+    left: Option<Rc<Node<K, V>>>,
+    right: Option<Rc<Node<K, V>>>,
+    // Memoized `_subtree_hash` result, cleared whenever key/val/children
+    // change so authenticated-mode trees don't re-hash untouched subtrees
+    // on every `root_hash`/`witness` call. Ignores `color`/`size`, which
+    // don't feed the hash, so `_flip_color` never needs to clear it.
+    subtree_hash: Cell<Option<[u8; 32]>>,
 }
 
 
@@ -19,15 +111,17 @@ impl<K, V> Node<K, V>  where K: Ord + Clone, V: Clone{
             key: Box::new(k),
             val: Box::new(v),
             color: true,
+            size: 1,
             left: None,
             right: None,
+            subtree_hash: Cell::new(None),
         }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct LLRBTree<K, V> where K: Ord + Clone, V: Clone {
-    root: Option<Box<Node<K, V>>>,
+    root: Option<Rc<Node<K, V>>>,
 }
 
 // public
@@ -41,14 +135,30 @@ impl<K, V> LLRBTree<K, V> where K: Ord + Clone, V: Clone{
     pub fn insert(&mut self, k: K, v: V) {
         self.root = LLRBTree::_insert(self.root.clone(), k, v);
         if let Some(ref mut r) = self.root {
-            r.color = false;
+            Rc::make_mut(r).color = false;
         }
     }
 
-    pub fn search(&self, k: K) -> Option<V> {
+    /// Like `insert`, but returns the value previously stored under `k`, if any.
+    /// Before the new node, or the box of an overwritten value, is allocated, a
+    /// fallible reservation of the same size is tried; if it fails, `Err` is
+    /// returned and the tree is left unchanged. Copies of shared nodes along the
+    /// insertion path and nodes rebuilt by rotations are allocated with the
+    /// infallible `Rc::new`/`Box::new`, so a failure there still aborts.
+    pub fn try_insert(&mut self, k: K, v: V) -> Result<Option<V>, TryReserveError> {
+        let (new_root, displaced) = LLRBTree::_try_insert(self.root.clone(), k, v)?;
+        self.root = new_root;
+        if let Some(ref mut r) = self.root {
+            Rc::make_mut(r).color = false;
+        }
+        Ok(displaced)
+    }
+
+    pub fn search<Q: Ord + ?Sized>(&self, k: &Q) -> Option<V> where K: Borrow<Q> {
         let mut x = &self.root;
         while let &Some(ref node) = x {
-            match (*node.key).cmp(&k) {
+            let key: &Q = (*node.key).borrow();
+            match key.cmp(k) {
                 Ordering::Equal => return Some(*node.val.clone()),
                 Ordering::Less => x = &node.right,
                 Ordering::Greater => x = &node.left,
@@ -56,18 +166,102 @@ impl<K, V> LLRBTree<K, V> where K: Ord + Clone, V: Clone{
         }
         None
     }
+
+    /// Returns an iterator, in ascending key order, over all entries whose
+    /// key falls within `r`.
+    pub fn range<R, Q>(&self, r: R) -> impl Iterator<Item = (&K, &V)>
+        where R: RangeBounds<Q>, Q: Ord + ?Sized, K: Borrow<Q> {
+        let mut out = Vec::new();
+        LLRBTree::_range_collect(&self.root, &r, &mut out);
+        out.into_iter()
+    }
+
+    pub fn delete(&mut self, k: &K) -> Option<V> {
+        if self.root.is_none() {
+            return None;
+        }
+        let (new_root, removed) = LLRBTree::_delete(self.root.take(), k);
+        self.root = new_root;
+        if let Some(ref mut r) = self.root {
+            Rc::make_mut(r).color = false;
+        }
+        removed
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut it = Iter { stack: Vec::new() };
+        it.push_left_spine(self.root.as_ref());
+        it
+    }
+
+    pub fn len(&self) -> usize {
+        LLRBTree::_size(&self.root)
+    }
+
+    /// Number of keys strictly less than `k`.
+    pub fn rank(&self, k: &K) -> usize {
+        let mut x = &self.root;
+        let mut r = 0;
+        while let &Some(ref node) = x {
+            match k.cmp(&node.key) {
+                Ordering::Less => x = &node.left,
+                Ordering::Equal => {
+                    r += LLRBTree::_size(&node.left);
+                    break;
+                },
+                Ordering::Greater => {
+                    r += LLRBTree::_size(&node.left) + 1;
+                    x = &node.right;
+                },
+            }
+        }
+        r
+    }
+
+    /// The `i`-th smallest key (zero-indexed), if the tree has that many keys.
+    pub fn select(&self, i: usize) -> Option<(&K, &V)> {
+        let mut x = &self.root;
+        let mut i = i;
+        while let &Some(ref node) = x {
+            let left_size = LLRBTree::_size(&node.left);
+            match i.cmp(&left_size) {
+                Ordering::Less => x = &node.left,
+                Ordering::Equal => return Some((&node.key, &node.val)),
+                Ordering::Greater => {
+                    i -= left_size + 1;
+                    x = &node.right;
+                },
+            }
+        }
+        None
+    }
+
+    /// Returns an independent handle to the tree's current contents in O(1),
+    /// sharing structure with `self` instead of copying it.
+    pub fn snapshot(&self) -> LLRBTree<K, V> {
+        LLRBTree { root: self.root.clone() }
+    }
 }
 
 // private
 impl<K, V> LLRBTree<K, V> where K: Ord + Clone, V: Clone{
-    fn _insert(h: Option<Box<Node<K, V>>>, k: K, v: V) -> Option<Box<Node<K, V>>> {
+    // Clones the node only if some other handle still shares it.
+    fn _unwrap_or_clone(rc: Rc<Node<K, V>>) -> Node<K, V> {
+        match Rc::try_unwrap(rc) {
+            Ok(node) => node,
+            Err(rc) => (*rc).clone(),
+        }
+    }
+
+    fn _insert(h: Option<Rc<Node<K, V>>>, k: K, v: V) -> Option<Rc<Node<K, V>>> {
         match h {
-            None => Some(Box::new(Node::<K, V>::new(k, v))),
-            Some(boxed_h) => {
-                let mut node = *boxed_h;
+            None => Some(Rc::new(Node::<K, V>::new(k, v))),
+            Some(rc_h) => {
+                let mut node = LLRBTree::_unwrap_or_clone(rc_h);
                 if LLRBTree::_should_flip_color(&node) {
                     LLRBTree::_flip_color(&mut node);
                 }
+                node.subtree_hash.set(None);
                 match (*node.key).cmp(&k) {
                     Ordering::Equal => node.val = Box::new(v),
                     Ordering::Less => node.right = LLRBTree::_insert(node.right, k, v),
@@ -86,11 +280,117 @@ impl<K, V> LLRBTree<K, V> where K: Ord + Clone, V: Clone{
                     LLRBTree::_flip_color(&mut node);
                 }
 
-                Some(Box::new(node))
+                node.size = 1 + LLRBTree::_size(&node.left) + LLRBTree::_size(&node.right);
+
+                Some(Rc::new(node))
+            },
+        }
+    }
+
+    fn _try_insert(h: Option<Rc<Node<K, V>>>, k: K, v: V) -> Result<(Option<Rc<Node<K, V>>>, Option<V>), TryReserveError> {
+        match h {
+            None => {
+                let node = LLRBTree::<K, V>::_try_rc_new(Node::<K, V>::new(k, v))?;
+                Ok((Some(node), None))
+            },
+            Some(rc_h) => {
+                let mut node = LLRBTree::_unwrap_or_clone(rc_h);
+                if LLRBTree::_should_flip_color(&node) {
+                    LLRBTree::_flip_color(&mut node);
+                }
+                node.subtree_hash.set(None);
+                let displaced;
+                match (*node.key).cmp(&k) {
+                    Ordering::Equal => {
+                        displaced = Some(*node.val);
+                        node.val = LLRBTree::<K, V>::_try_box_new(v)?;
+                    },
+                    Ordering::Less => {
+                        let (new_right, d) = LLRBTree::_try_insert(node.right, k, v)?;
+                        node.right = new_right;
+                        displaced = d;
+                    },
+                    Ordering::Greater => {
+                        let (new_left, d) = LLRBTree::_try_insert(node.left, k, v)?;
+                        node.left = new_left;
+                        displaced = d;
+                    },
+                }
+
+                if LLRBTree::_should_rotate_left(&node.left, &node.right) {
+                    node = LLRBTree::_rotate_left(node);
+                }
+
+                if LLRBTree::_should_rotate_right(&node.left) {
+                    node = LLRBTree::_rotate_right(node);
+                }
+
+                if LLRBTree::_should_flip_color(&node) {
+                    LLRBTree::_flip_color(&mut node);
+                }
+
+                node.size = 1 + LLRBTree::_size(&node.left) + LLRBTree::_size(&node.right);
+
+                let rc = LLRBTree::<K, V>::_try_rc_new(node)?;
+                Ok((Some(rc), displaced))
             },
         }
     }
 
+    // Probes allocability via a throwaway `Vec` since `Box::try_new` needs
+    // the unstable `allocator_api` feature.
+    fn _try_box_new<T>(value: T) -> Result<Box<T>, TryReserveError> {
+        let mut probe: Vec<T> = Vec::new();
+        probe.try_reserve_exact(1)?;
+        Ok(Box::new(value))
+    }
+
+    // Like `_try_box_new`, sized for `Rc`'s layout (strong/weak counters
+    // plus `T`) so the probe doesn't under-report the real allocation.
+    fn _try_rc_new<T>(value: T) -> Result<Rc<T>, TryReserveError> {
+        #[allow(dead_code)]
+        struct RcAllocLayout<T> {
+            counters: (usize, usize),
+            value: T,
+        }
+        let mut probe: Vec<RcAllocLayout<T>> = Vec::new();
+        probe.try_reserve_exact(1)?;
+        Ok(Rc::new(value))
+    }
+
+    fn _size(node: &Option<Rc<Node<K, V>>>) -> usize {
+        match node {
+            &Some(ref n) => n.size,
+            &None => 0,
+        }
+    }
+
+    fn _range_collect<'a, R, Q>(h: &'a Option<Rc<Node<K, V>>>, r: &R, out: &mut Vec<(&'a K, &'a V)>)
+        where R: RangeBounds<Q>, Q: Ord + ?Sized, K: Borrow<Q> {
+        if let &Some(ref node) = h {
+            let key: &Q = (*node.key).borrow();
+            let below_lower = match r.start_bound() {
+                Bound::Included(lo) => key < lo,
+                Bound::Excluded(lo) => key <= lo,
+                Bound::Unbounded => false,
+            };
+            let above_upper = match r.end_bound() {
+                Bound::Included(hi) => key > hi,
+                Bound::Excluded(hi) => key >= hi,
+                Bound::Unbounded => false,
+            };
+            if !below_lower {
+                LLRBTree::_range_collect(&node.left, r, out);
+            }
+            if !below_lower && !above_upper {
+                out.push((&node.key, &node.val));
+            }
+            if !above_upper {
+                LLRBTree::_range_collect(&node.right, r, out);
+            }
+        }
+    }
+
     fn _should_flip_color(node: &Node<K, V>) -> bool {
         match (&node.left, &node.right) {
             (&Some(ref l), &Some(ref r)) => {
@@ -107,16 +407,16 @@ impl<K, V> LLRBTree<K, V> where K: Ord + Clone, V: Clone{
     fn _flip_color(h: &mut Node<K, V>) {
         h.color = !h.color;
         match h.left {
-            Some(ref mut left) => left.color = !left.color,
+            Some(ref mut left) => Rc::make_mut(left).color = !left.color,
             _ => unreachable!(),
         }
         match h.right {
-            Some(ref mut right) => right.color = !right.color,
+            Some(ref mut right) => Rc::make_mut(right).color = !right.color,
             _ => unreachable!(),
         }
     }
 
-    fn _should_rotate_left(left: &Option<Box<Node<K, V>>>, right: &Option<Box<Node<K, V>>>) -> bool {
+    fn _should_rotate_left(left: &Option<Rc<Node<K, V>>>, right: &Option<Rc<Node<K, V>>>) -> bool {
         match (right, left) {
             (&Some(ref r), &None) => r.color,
             (&Some(ref r), &Some(ref l)) => r.color && !l.color,
@@ -124,7 +424,7 @@ impl<K, V> LLRBTree<K, V> where K: Ord + Clone, V: Clone{
         }
     }
 
-    fn _should_rotate_right(left: &Option<Box<Node<K, V>>>) -> bool {
+    fn _should_rotate_right(left: &Option<Rc<Node<K, V>>>) -> bool {
         match left {
             &None => false,
             &Some(ref l) => {
@@ -138,42 +438,412 @@ impl<K, V> LLRBTree<K, V> where K: Ord + Clone, V: Clone{
 
     fn _rotate_left(node_h: Node<K, V>) -> Node<K, V> {
         let Node{ color, right, ..} = node_h;
-        let node_x = *right.unwrap();
+        let node_x = LLRBTree::_unwrap_or_clone(right.unwrap());
+        let new_h_size = 1 + LLRBTree::_size(&node_h.left) + LLRBTree::_size(&node_x.left);
+        let top_size = 1 + new_h_size + LLRBTree::_size(&node_x.right);
         let new_h = Node {
             key: node_h.key,
             val: node_h.val,
             color: true,
+            size: new_h_size,
             left: node_h.left,
             right: node_x.left,
+            subtree_hash: Cell::new(None),
         };
 
         Node {
             key: node_x.key,
             val: node_x.val,
             color: color,
-            left: Some(Box::new(new_h)),
+            size: top_size,
+            left: Some(Rc::new(new_h)),
             right: node_x.right,
+            subtree_hash: Cell::new(None),
         }
     }
 
     fn _rotate_right(node_h: Node<K, V>) -> Node<K, V> {
         let Node{ color, left, ..} = node_h;
-        let node_x = *left.unwrap();
+        let node_x = LLRBTree::_unwrap_or_clone(left.unwrap());
+        let new_h_size = 1 + LLRBTree::_size(&node_x.right) + LLRBTree::_size(&node_h.right);
+        let top_size = 1 + LLRBTree::_size(&node_x.left) + new_h_size;
         let new_h = Node {
             key: node_h.key,
             val: node_h.val,
             color: true,
+            size: new_h_size,
             left: node_x.right,
             right: node_h.right,
+            subtree_hash: Cell::new(None),
         };
 
         Node {
             key: node_x.key,
             val: node_x.val,
             color: color,
+            size: top_size,
             left: node_x.left,
-            right: Some(Box::new(new_h)),
+            right: Some(Rc::new(new_h)),
+            subtree_hash: Cell::new(None),
+        }
+    }
+
+    fn _is_red(node: &Option<Rc<Node<K, V>>>) -> bool {
+        match node {
+            &Some(ref n) => n.color,
+            &None => false,
+        }
+    }
+
+    fn _fix_up(mut h: Node<K, V>) -> Node<K, V> {
+        if LLRBTree::_is_red(&h.right) && !LLRBTree::_is_red(&h.left) {
+            h = LLRBTree::_rotate_left(h);
+        }
+        let left_left_red = match &h.left {
+            &Some(ref l) => LLRBTree::_is_red(&l.left),
+            &None => false,
+        };
+        if LLRBTree::_is_red(&h.left) && left_left_red {
+            h = LLRBTree::_rotate_right(h);
+        }
+        if LLRBTree::_is_red(&h.left) && LLRBTree::_is_red(&h.right) {
+            LLRBTree::_flip_color(&mut h);
+        }
+        h
+    }
+
+    fn _move_red_left(mut h: Node<K, V>) -> Node<K, V> {
+        LLRBTree::_flip_color(&mut h);
+        let should_rotate = match &h.right {
+            &Some(ref r) => LLRBTree::_is_red(&r.left),
+            &None => false,
+        };
+        if should_rotate {
+            let right = LLRBTree::_unwrap_or_clone(h.right.take().unwrap());
+            h.right = Some(Rc::new(LLRBTree::_rotate_right(right)));
+            h = LLRBTree::_rotate_left(h);
+            LLRBTree::_flip_color(&mut h);
+        }
+        h
+    }
+
+    fn _move_red_right(mut h: Node<K, V>) -> Node<K, V> {
+        LLRBTree::_flip_color(&mut h);
+        let should_rotate = match &h.left {
+            &Some(ref l) => LLRBTree::_is_red(&l.left),
+            &None => false,
+        };
+        if should_rotate {
+            h = LLRBTree::_rotate_right(h);
+            LLRBTree::_flip_color(&mut h);
+        }
+        h
+    }
+
+    // Removes the smallest node in the subtree rooted at `h`, returning the
+    // new subtree root along with the removed key/val.
+    fn _delete_min(h: Node<K, V>) -> (Option<Rc<Node<K, V>>>, K, V) {
+        if h.left.is_none() {
+            return (None, *h.key, *h.val);
+        }
+        let mut h = h;
+        let left_red = LLRBTree::_is_red(&h.left);
+        let left_left_red = match &h.left {
+            &Some(ref l) => LLRBTree::_is_red(&l.left),
+            &None => false,
+        };
+        if !left_red && !left_left_red {
+            h = LLRBTree::_move_red_left(h);
+        }
+        let left = h.left.take().unwrap();
+        let (new_left, k, v) = LLRBTree::_delete_min(LLRBTree::_unwrap_or_clone(left));
+        h.left = new_left;
+        h.subtree_hash.set(None);
+        h.size = 1 + LLRBTree::_size(&h.left) + LLRBTree::_size(&h.right);
+        let h = LLRBTree::_fix_up(h);
+        (Some(Rc::new(h)), k, v)
+    }
+
+    fn _delete(h: Option<Rc<Node<K, V>>>, k: &K) -> (Option<Rc<Node<K, V>>>, Option<V>) {
+        match h {
+            None => (None, None),
+            Some(rc_h) => {
+                let mut node = LLRBTree::_unwrap_or_clone(rc_h);
+                let removed;
+                if k.cmp(&node.key) == Ordering::Less {
+                    if node.left.is_none() {
+                        // key is not in the tree; nothing below this node to search.
+                        let node = LLRBTree::_fix_up(node);
+                        return (Some(Rc::new(node)), None);
+                    }
+                    let left_red = LLRBTree::_is_red(&node.left);
+                    let left_left_red = match &node.left {
+                        &Some(ref l) => LLRBTree::_is_red(&l.left),
+                        &None => false,
+                    };
+                    if !left_red && !left_left_red {
+                        node = LLRBTree::_move_red_left(node);
+                    }
+                    let left = node.left.take();
+                    let (new_left, rem) = LLRBTree::_delete(left, k);
+                    node.left = new_left;
+                    removed = rem;
+                } else {
+                    if LLRBTree::_is_red(&node.left) {
+                        node = LLRBTree::_rotate_right(node);
+                    }
+                    if k.cmp(&node.key) == Ordering::Equal && node.right.is_none() {
+                        return (None, Some(*node.val));
+                    }
+                    if node.right.is_none() {
+                        // key is not in the tree; nothing below this node to search.
+                        let node = LLRBTree::_fix_up(node);
+                        return (Some(Rc::new(node)), None);
+                    }
+                    let right_red = LLRBTree::_is_red(&node.right);
+                    let right_left_red = match &node.right {
+                        &Some(ref r) => LLRBTree::_is_red(&r.left),
+                        &None => false,
+                    };
+                    if !right_red && !right_left_red {
+                        node = LLRBTree::_move_red_right(node);
+                    }
+                    if k.cmp(&node.key) == Ordering::Equal {
+                        let right = node.right.take().unwrap();
+                        let (new_right, min_k, min_v) = LLRBTree::_delete_min(LLRBTree::_unwrap_or_clone(right));
+                        removed = Some(*node.val);
+                        node.key = Box::new(min_k);
+                        node.val = Box::new(min_v);
+                        node.right = new_right;
+                    } else {
+                        let right = node.right.take();
+                        let (new_right, rem) = LLRBTree::_delete(right, k);
+                        node.right = new_right;
+                        removed = rem;
+                    }
+                }
+                node.subtree_hash.set(None);
+                node.size = 1 + LLRBTree::_size(&node.left) + LLRBTree::_size(&node.right);
+                let node = LLRBTree::_fix_up(node);
+                (Some(Rc::new(node)), removed)
+            },
+        }
+    }
+
+    fn _collect_into(h: Option<Rc<Node<K, V>>>, out: &mut Vec<(K, V)>) {
+        if let Some(rc) = h {
+            let node = LLRBTree::_unwrap_or_clone(rc);
+            LLRBTree::_collect_into(node.left, out);
+            out.push((*node.key, *node.val));
+            LLRBTree::_collect_into(node.right, out);
+        }
+    }
+}
+
+// Authenticated mode: opt-in by having both K and V expose their bytes, so
+// a tree can compute a root hash and produce inclusion/exclusion proofs
+// without requiring every user of `LLRBTree` to pay for hashing.
+impl<K, V> LLRBTree<K, V> where K: Ord + Clone + AsRef<[u8]>, V: Clone + AsRef<[u8]> {
+    /// Hash of the tree's current shape and contents. This is computed
+    /// bottom-up over the tree structure, not the sorted key/value
+    /// sequence, so it depends on insertion/deletion history: two trees
+    /// holding the same entries can have different root hashes if they
+    /// were assembled in a different order. Rebuilding the same history
+    /// (or cloning/`snapshot`ing a tree) always reproduces the same hash.
+    /// Per-node hashes are cached and only recomputed for subtrees touched
+    /// since the last call, so repeated calls on an unchanged tree are cheap.
+    pub fn root_hash(&self) -> [u8; 32] {
+        LLRBTree::_subtree_hash(&self.root)
+    }
+
+    /// Produces a proof that `k` either is present (with its value) or is
+    /// absent (bracketed by its in-order neighbors) in this tree, checkable
+    /// against `root_hash()` via `verify` without the full tree.
+    pub fn witness(&self, k: &K) -> Proof<K, V> {
+        let mut steps = Vec::new();
+        let mut x = &self.root;
+        let mut lower = None;
+        let mut upper = None;
+        loop {
+            match x {
+                &Some(ref node) => {
+                    match (*node.key).cmp(k) {
+                        Ordering::Equal => {
+                            return Proof {
+                                steps,
+                                outcome: ProofOutcome::Included {
+                                    key: (*node.key).clone(),
+                                    val: (*node.val).clone(),
+                                    left_hash: LLRBTree::_subtree_hash(&node.left),
+                                    right_hash: LLRBTree::_subtree_hash(&node.right),
+                                },
+                            };
+                        },
+                        Ordering::Less => {
+                            lower = Some(((*node.key).clone(), (*node.val).clone()));
+                            let leaf = LLRBTree::_leaf_hash(&*node.key, &*node.val);
+                            steps.push((Direction::Right, LLRBTree::_subtree_hash(&node.left), leaf));
+                            x = &node.right;
+                        },
+                        Ordering::Greater => {
+                            upper = Some(((*node.key).clone(), (*node.val).clone()));
+                            let leaf = LLRBTree::_leaf_hash(&*node.key, &*node.val);
+                            steps.push((Direction::Left, LLRBTree::_subtree_hash(&node.right), leaf));
+                            x = &node.left;
+                        },
+                    }
+                },
+                &None => {
+                    return Proof { steps, outcome: ProofOutcome::Excluded { lower, upper } };
+                },
+            }
+        }
+    }
+
+    fn _leaf_hash(k: &K, v: &V) -> [u8; 32] {
+        hash_bytes(&[k.as_ref(), v.as_ref()])
+    }
+
+    fn _subtree_hash(h: &Option<Rc<Node<K, V>>>) -> [u8; 32] {
+        match h {
+            &Some(ref node) => {
+                if let Some(cached) = node.subtree_hash.get() {
+                    return cached;
+                }
+                let left = LLRBTree::_subtree_hash(&node.left);
+                let right = LLRBTree::_subtree_hash(&node.right);
+                let leaf = LLRBTree::_leaf_hash(&*node.key, &*node.val);
+                let computed = hash_bytes(&[&leaf, &left, &right]);
+                node.subtree_hash.set(Some(computed));
+                computed
+            },
+            &None => EMPTY_HASH,
+        }
+    }
+}
+
+/// Which branch a `witness` step descended through; the sibling hash
+/// carried alongside it is the subtree that was *not* taken.
+enum Direction {
+    Left,
+    Right,
+}
+
+enum ProofOutcome<K, V> {
+    Included { key: K, val: V, left_hash: [u8; 32], right_hash: [u8; 32] },
+    Excluded { lower: Option<(K, V)>, upper: Option<(K, V)> },
+}
+
+/// An inclusion or exclusion proof produced by `LLRBTree::witness`, checkable
+/// against a root hash via `verify` without access to the full tree.
+pub struct Proof<K, V> {
+    steps: Vec<(Direction, [u8; 32], [u8; 32])>,
+    outcome: ProofOutcome<K, V>,
+}
+
+fn fold_proof(mut acc: [u8; 32], steps: &[(Direction, [u8; 32], [u8; 32])]) -> [u8; 32] {
+    for &(ref dir, ref sibling, ref leaf) in steps.iter().rev() {
+        acc = match dir {
+            &Direction::Left => hash_bytes(&[leaf, &acc, sibling]),
+            &Direction::Right => hash_bytes(&[leaf, sibling, &acc]),
+        };
+    }
+    acc
+}
+
+/// Checks a `Proof` from `LLRBTree::witness` against a trusted `root_hash`.
+/// `value` is `Some` to check inclusion of `(key, value)`, or `None` to check
+/// that `key` is absent.
+pub fn verify<K, V>(root_hash: [u8; 32], key: &K, value: Option<&V>, proof: &Proof<K, V>) -> bool
+    where K: Ord + AsRef<[u8]>, V: AsRef<[u8]> {
+    match (&proof.outcome, value) {
+        (&ProofOutcome::Included { key: ref pk, val: ref pv, left_hash, right_hash }, Some(v)) => {
+            if key.cmp(pk) != Ordering::Equal || v.as_ref() != pv.as_ref() {
+                return false;
+            }
+            let leaf = hash_bytes(&[key.as_ref(), v.as_ref()]);
+            let current = hash_bytes(&[&leaf, &left_hash, &right_hash]);
+            fold_proof(current, &proof.steps) == root_hash
+        },
+        (&ProofOutcome::Excluded { lower: ref lo, upper: ref up }, None) => {
+            let brackets_ok = match (lo, up) {
+                (&Some((ref lk, _)), &Some((ref uk, _))) => lk.cmp(key) == Ordering::Less && key.cmp(uk) == Ordering::Less,
+                (&Some((ref lk, _)), &None) => lk.cmp(key) == Ordering::Less,
+                (&None, &Some((ref uk, _))) => key.cmp(uk) == Ordering::Less,
+                (&None, &None) => true,
+            };
+            brackets_ok && fold_proof(EMPTY_HASH, &proof.steps) == root_hash
+        },
+        _ => false,
+    }
+}
+
+/// Borrowed in-order iterator over a `LLRBTree`, yielding `(&K, &V)` pairs
+/// from smallest key to largest.
+pub struct Iter<'a, K: 'a, V: 'a> where K: Ord + Clone, V: Clone {
+    stack: Vec<&'a Node<K, V>>,
+}
+
+impl<'a, K, V> Iter<'a, K, V> where K: Ord + Clone, V: Clone {
+    fn push_left_spine(&mut self, mut node: Option<&'a Rc<Node<K, V>>>) {
+        while let Some(n) = node {
+            self.stack.push(n);
+            node = n.left.as_ref();
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> where K: Ord + Clone, V: Clone {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_left_spine(node.right.as_ref());
+        Some((&node.key, &node.val))
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a LLRBTree<K, V> where K: Ord + Clone, V: Clone {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+/// Owning in-order iterator over a `LLRBTree`, yielding `(K, V)` pairs
+/// from smallest key to largest.
+pub struct IntoIter<K, V> where K: Ord + Clone, V: Clone {
+    items: ::std::vec::IntoIter<(K, V)>,
+}
+
+impl<K, V> Iterator for IntoIter<K, V> where K: Ord + Clone, V: Clone {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.items.next()
+    }
+}
+
+impl<K, V> IntoIterator for LLRBTree<K, V> where K: Ord + Clone, V: Clone {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> IntoIter<K, V> {
+        let mut items = Vec::new();
+        LLRBTree::_collect_into(self.root, &mut items);
+        IntoIter { items: items.into_iter() }
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for LLRBTree<K, V> where K: Ord + Clone, V: Clone {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> LLRBTree<K, V> {
+        let mut t = LLRBTree::new();
+        for (k, v) in iter {
+            t.insert(k, v);
         }
+        t
     }
 }
 
@@ -182,9 +852,9 @@ fn test1() {
     let mut t = LLRBTree::<usize, usize>::new();
     t.insert(5,1);
     t.insert(6,2);
-    assert_eq!(1, t.search(5).unwrap());
-    assert_eq!(2, t.search(6).unwrap());
-    assert_eq!(None, t.search(9));
+    assert_eq!(1, t.search(&5).unwrap());
+    assert_eq!(2, t.search(&6).unwrap());
+    assert_eq!(None, t.search(&9));
 }
 
 #[test]
@@ -194,7 +864,281 @@ fn test2() {
     t.insert("Bar".to_string(),'b');
     t.insert("Quux".to_string(),'q');
     t.insert("fooz".to_string(),'F');
-    assert_eq!('b', t.search("Bar".to_string()).unwrap());
-    assert_eq!('q', t.search("Quux".to_string()).unwrap());
-    assert_eq!(None, t.search("OOO".to_string()));
-}
\ No newline at end of file
+    assert_eq!('b', t.search("Bar").unwrap());
+    assert_eq!('q', t.search("Quux").unwrap());
+    assert_eq!(None, t.search("OOO"));
+}
+
+#[test]
+fn test_delete() {
+    let mut t = LLRBTree::<usize, usize>::new();
+    for i in 0..100 {
+        t.insert(i, i * 10);
+    }
+    for i in 0..100 {
+        if i % 2 == 0 {
+            assert_eq!(Some(i * 10), t.delete(&i));
+        }
+    }
+    for i in 0..100 {
+        if i % 2 == 0 {
+            assert_eq!(None, t.search(&i));
+        } else {
+            assert_eq!(Some(i * 10), t.search(&i));
+        }
+    }
+    assert_eq!(None, t.delete(&1000));
+}
+
+#[test]
+fn test_delete_missing_key_below_minimum() {
+    let mut t = LLRBTree::<usize, usize>::new();
+    t.insert(22, 177);
+    assert_eq!(None, t.delete(&21));
+    assert_eq!(Some(177), t.search(&22));
+
+    let mut t = LLRBTree::<usize, usize>::new();
+    for i in 10..20 {
+        t.insert(i, i * 10);
+    }
+    assert_eq!(None, t.delete(&0));
+    for i in 10..20 {
+        assert_eq!(Some(i * 10), t.search(&i));
+    }
+}
+
+#[test]
+fn test_delete_to_empty() {
+    let mut t = LLRBTree::<&str, i32>::new();
+    t.insert("a", 1);
+    t.insert("b", 2);
+    t.insert("c", 3);
+    assert_eq!(Some(1), t.delete(&"a"));
+    assert_eq!(Some(2), t.delete(&"b"));
+    assert_eq!(Some(3), t.delete(&"c"));
+    assert_eq!(None, t.search(&"a"));
+}
+
+#[test]
+fn test_iter_sorted_order() {
+    let mut t = LLRBTree::<usize, usize>::new();
+    for i in [5, 1, 9, 3, 7, 2, 8].iter() {
+        t.insert(*i, i * 100);
+    }
+    let collected: Vec<(usize, usize)> = t.iter().map(|(k, v)| (*k, *v)).collect();
+    assert_eq!(vec![(1, 100), (2, 200), (3, 300), (5, 500), (7, 700), (8, 800), (9, 900)], collected);
+
+    let mut by_ref: Vec<(usize, usize)> = Vec::new();
+    for (k, v) in &t {
+        by_ref.push((*k, *v));
+    }
+    assert_eq!(collected, by_ref);
+}
+
+#[test]
+fn test_into_iter_and_from_iter() {
+    let mut t = LLRBTree::<usize, usize>::new();
+    t.insert(3, 30);
+    t.insert(1, 10);
+    t.insert(2, 20);
+
+    let owned: Vec<(usize, usize)> = t.into_iter().collect();
+    assert_eq!(vec![(1, 10), (2, 20), (3, 30)], owned);
+
+    let rebuilt: LLRBTree<usize, usize> = owned.into_iter().collect();
+    assert_eq!(Some(10), rebuilt.search(&1));
+    assert_eq!(Some(20), rebuilt.search(&2));
+    assert_eq!(Some(30), rebuilt.search(&3));
+}
+
+#[test]
+fn test_len_rank_select() {
+    let mut t = LLRBTree::<usize, usize>::new();
+    assert_eq!(0, t.len());
+    let keys = [5, 1, 9, 3, 7, 2, 8, 0, 4, 6];
+    for k in keys.iter() {
+        t.insert(*k, k * 10);
+    }
+    assert_eq!(keys.len(), t.len());
+
+    for i in 0..keys.len() {
+        assert_eq!(i, t.rank(&i));
+        assert_eq!(Some((&i, &(i * 10))), t.select(i));
+    }
+    assert_eq!(keys.len(), t.rank(&100));
+    assert_eq!(None, t.select(100));
+
+    t.delete(&5);
+    assert_eq!(keys.len() - 1, t.len());
+    assert_eq!(5, t.rank(&6));
+    assert_eq!(Some((&6, &60)), t.select(5));
+}
+
+#[test]
+fn test_range() {
+    let mut t = LLRBTree::<usize, usize>::new();
+    for i in 0..10 {
+        t.insert(i, i * 10);
+    }
+    let inclusive: Vec<(usize, usize)> = t.range(3..=6).map(|(k, v)| (*k, *v)).collect();
+    assert_eq!(vec![(3, 30), (4, 40), (5, 50), (6, 60)], inclusive);
+
+    let exclusive: Vec<(usize, usize)> = t.range(3..6).map(|(k, v)| (*k, *v)).collect();
+    assert_eq!(vec![(3, 30), (4, 40), (5, 50)], exclusive);
+
+    let from_start: Vec<(usize, usize)> = t.range(..3).map(|(k, v)| (*k, *v)).collect();
+    assert_eq!(vec![(0, 0), (1, 10), (2, 20)], from_start);
+
+    let unbounded: Vec<(usize, usize)> = t.range(..).map(|(k, v)| (*k, *v)).collect();
+    assert_eq!(10, unbounded.len());
+}
+
+#[test]
+fn test_range_borrowed_str_key() {
+    let mut t = LLRBTree::<String, i32>::new();
+    t.insert("apple".to_string(), 1);
+    t.insert("banana".to_string(), 2);
+    t.insert("cherry".to_string(), 3);
+    t.insert("date".to_string(), 4);
+
+    let got: Vec<&str> = t.range("banana".to_string().."date".to_string())
+        .map(|(k, _)| k.as_str())
+        .collect();
+    assert_eq!(vec!["banana", "cherry"], got);
+
+    assert_eq!(Some(2), t.search("banana"));
+}
+
+#[test]
+fn test_hash_bytes_matches_known_sha256_vectors() {
+    fn hex(bytes: &[u8; 32]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+    assert_eq!(
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        hex(&hash_bytes(&[])),
+    );
+    assert_eq!(
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        hex(&hash_bytes(&[b"abc"])),
+    );
+    assert_eq!(
+        hash_bytes(&[b"ab", b"c"]),
+        hash_bytes(&[b"abc"]),
+        "hash_bytes folds its parts as one message, not delimited per-part",
+    );
+}
+
+#[test]
+fn test_root_hash_depends_on_structure_not_just_contents() {
+    // Same insertion history reproduces the same hash.
+    let mut a = LLRBTree::<String, String>::new();
+    a.insert("a".to_string(), "1".to_string());
+    a.insert("b".to_string(), "2".to_string());
+    a.insert("c".to_string(), "3".to_string());
+
+    let mut a_again = LLRBTree::<String, String>::new();
+    a_again.insert("a".to_string(), "1".to_string());
+    a_again.insert("b".to_string(), "2".to_string());
+    a_again.insert("c".to_string(), "3".to_string());
+
+    assert_eq!(a.root_hash(), a_again.root_hash());
+
+    let mut c = a.clone();
+    c.insert("d".to_string(), "4".to_string());
+    assert_ne!(a.root_hash(), c.root_hash());
+
+    // Same entries, different insertion order: the root hash is a
+    // function of tree shape, so it is not guaranteed to match. A handful
+    // of keys can coincidentally land on the same shape either way, so
+    // use enough keys that ascending vs. descending insertion reliably
+    // produces different trees.
+    let mut ascending = LLRBTree::<String, String>::new();
+    for i in 0..30 {
+        ascending.insert(format!("{:03}", i), format!("{:03}", i));
+    }
+    let mut descending = LLRBTree::<String, String>::new();
+    for i in (0..30).rev() {
+        descending.insert(format!("{:03}", i), format!("{:03}", i));
+    }
+    assert_ne!(ascending.root_hash(), descending.root_hash());
+}
+
+#[test]
+fn test_root_hash_caches_across_repeated_calls() {
+    use std::time::Instant;
+
+    let mut t = LLRBTree::<String, String>::new();
+    for i in 0..20_000 {
+        t.insert(format!("{:06}", i), format!("{:06}", i));
+    }
+
+    let first = t.root_hash();
+    let start = Instant::now();
+    for _ in 0..200 {
+        assert_eq!(first, t.root_hash());
+    }
+    let elapsed = start.elapsed();
+    assert!(
+        elapsed.as_millis() < 200,
+        "200 repeated root_hash() calls on an unchanged tree took {:?}; \
+         expected cache hits to stay near-instant instead of re-hashing the whole tree",
+        elapsed,
+    );
+}
+
+#[test]
+fn test_witness_inclusion_and_exclusion() {
+    let mut t = LLRBTree::<String, String>::new();
+    for (k, v) in [("a", "1"), ("b", "2"), ("c", "3"), ("d", "4"), ("e", "5")].iter() {
+        t.insert(k.to_string(), v.to_string());
+    }
+    let root = t.root_hash();
+
+    let proof = t.witness(&"c".to_string());
+    assert!(verify(root, &"c".to_string(), Some(&"3".to_string()), &proof));
+    assert!(!verify(root, &"c".to_string(), Some(&"wrong".to_string()), &proof));
+
+    let missing = t.witness(&"cc".to_string());
+    assert!(verify(root, &"cc".to_string(), None, &missing));
+    assert!(!verify(root, &"a".to_string(), None, &missing));
+
+    let before_start = t.witness(&"0".to_string());
+    assert!(verify(root, &"0".to_string(), None, &before_start));
+
+    let after_end = t.witness(&"z".to_string());
+    assert!(verify(root, &"z".to_string(), None, &after_end));
+}
+
+#[test]
+fn test_try_insert() {
+    let mut t = LLRBTree::<usize, usize>::new();
+    assert_eq!(Ok(None), t.try_insert(1, 10));
+    assert_eq!(Ok(None), t.try_insert(2, 20));
+    assert_eq!(Ok(Some(10)), t.try_insert(1, 100));
+    assert_eq!(Some(100), t.search(&1));
+    assert_eq!(Some(20), t.search(&2));
+    assert_eq!(2, t.len());
+}
+
+#[test]
+fn test_snapshot_is_independent_after_mutation() {
+    let mut t = LLRBTree::<usize, usize>::new();
+    for i in 0..20 {
+        t.insert(i, i * 10);
+    }
+
+    let snap = t.snapshot();
+
+    t.insert(100, 1000);
+    assert_eq!(None, snap.search(&100));
+    assert_eq!(Some(1000), t.search(&100));
+
+    t.delete(&5);
+    assert_eq!(Some(50), snap.search(&5));
+    assert_eq!(None, t.search(&5));
+
+    assert_eq!(20, snap.len());
+    let collected: Vec<(usize, usize)> = snap.iter().map(|(k, v)| (*k, *v)).collect();
+    assert_eq!((0..20).map(|i| (i, i * 10)).collect::<Vec<_>>(), collected);
+}